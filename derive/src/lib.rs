@@ -0,0 +1,81 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields};
+
+/// Derives `ToTable` and `ToTableRows` for a named-field struct: field names
+/// become the table header and each field's `Display`/`to_string()` becomes
+/// its row cell. `ToTable` builds a standalone single-row table; `ToTableRows`
+/// lets a collection of these items share one table.
+///
+/// Per-field `#[table(...)]` attributes customize the generated column:
+/// - `#[table(rename = "...")]` overrides the header cell for that field.
+/// - `#[table(skip)]` omits the field from the table entirely.
+#[proc_macro_derive(ToTable, attributes(table))]
+pub fn derive_to_table(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => panic!("ToTable can only be derived for structs with named fields"),
+        },
+        _ => panic!("ToTable can only be derived for structs"),
+    };
+
+    let mut headers = Vec::new();
+    let mut cells = Vec::new();
+
+    for field in fields {
+        let mut rename = None;
+        let mut skip = false;
+
+        for attr in &field.attrs {
+            if !attr.path().is_ident("table") {
+                continue;
+            }
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("skip") {
+                    skip = true;
+                } else if meta.path.is_ident("rename") {
+                    let value: syn::LitStr = meta.value()?.parse()?;
+                    rename = Some(value.value());
+                }
+                Ok(())
+            })
+            .expect("invalid #[table(...)] attribute");
+        }
+
+        if skip {
+            continue;
+        }
+
+        let ident = field.ident.as_ref().expect("named field");
+        headers.push(rename.unwrap_or_else(|| ident.to_string()));
+        cells.push(quote! { self.#ident.to_string() });
+    }
+
+    let expanded = quote! {
+        impl ToTable for #name {
+            fn to_table(&self) -> ::comfy_table::Table {
+                let mut table = ::comfy_table::Table::new();
+                table
+                    .set_content_arrangement(::comfy_table::ContentArrangement::Dynamic)
+                    .set_header(Self::header())
+                    .add_row(self.to_row());
+                table
+            }
+        }
+
+        impl ToTableRows for #name {
+            fn header() -> ::comfy_table::Row {
+                ::comfy_table::Row::from(vec![#(#headers),*])
+            }
+            fn to_row(&self) -> ::comfy_table::Row {
+                ::comfy_table::Row::from(vec![#(#cells),*])
+            }
+        }
+    };
+
+    TokenStream::from(expanded)
+}
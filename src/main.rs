@@ -1,214 +1,180 @@
 use clap::Parser;
+use clap_output_formats_derive::ToTable;
 use comfy_table::{ContentArrangement, Row, Table};
 use std::fmt;
+use std::io;
 
-trait DisplayType<X> {
-    fn next_fmt(&self, x: &X) -> Option<String>;
-    fn fmt(&self, x: &X) -> String;
-    fn display(&self, x: &X) -> String {
-        self.next_fmt(x).unwrap_or_else(|| self.fmt(x))
-    }
+/// Errors that can occur while rendering a value in one of the supported
+/// output formats.
+#[derive(Debug, thiserror::Error)]
+enum Error {
+    #[error("cannot serialize item to JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("cannot serialize item to YAML: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("cannot serialize item to TOML: {0}")]
+    Toml(#[from] toml::ser::Error),
 }
 
 trait ToTable {
     fn to_table(&self) -> Table;
 }
 
-#[derive(Parser)]
-struct NoDisplay {}
-
-impl<X> DisplayType<X> for NoDisplay {
-    fn next_fmt(&self, _: &X) -> Option<String> {
-        None
-    }
-    fn fmt(&self, _: &X) -> String {
-        String::new()
-    }
+/// Implemented by types that can contribute a row to a table shared by a
+/// whole collection, as opposed to [`ToTable`] which builds a standalone
+/// single-item table.
+trait ToTableRows {
+    fn header() -> Row;
+    fn to_row(&self) -> Row;
 }
 
-#[derive(Parser)]
-struct TextDisplay<T: clap::Args> {
-    /// Display as text
-    #[clap(long, group = "fmt_type")]
-    text: bool,
-    #[clap(flatten)]
-    next: T,
-}
+/// A collection of items to be rendered together: one JSON/YAML document
+/// for the whole slice, or one table with a shared header and a row per
+/// item, rather than one table per item.
+#[derive(Debug)]
+struct Items<X>(Vec<X>);
 
-impl<X, T> DisplayType<X> for TextDisplay<T>
-where
-    X: fmt::Display,
-    T: DisplayType<X> + clap::Args,
-{
-    fn next_fmt(&self, x: &X) -> Option<String> {
-        self.text
-            .then(|| self.fmt(x))
-            .or_else(|| self.next.next_fmt(x))
-    }
-    fn fmt(&self, x: &X) -> String {
-        format!("{x}")
+impl<X: serde::Serialize> serde::Serialize for Items<X> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        // Serializing the bare `Vec` would put a sequence at the document's
+        // top level, which TOML has no syntax for. Wrapping it in a named
+        // field keeps every serde-based format (including TOML, as an
+        // array of tables) able to represent a collection.
+        use serde::ser::SerializeStruct;
+        let mut state = serializer.serialize_struct("Items", 1)?;
+        state.serialize_field("items", &self.0)?;
+        state.end()
     }
 }
 
-#[derive(Parser)]
-struct DebugDisplay<T: clap::Args> {
-    /// Display as internal debug representation
-    #[clap(long, group = "fmt_type")]
-    debug: bool,
-    #[clap(flatten)]
-    next: T,
+impl<X: fmt::Display> fmt::Display for Items<X> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, item) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{item}")?;
+        }
+        Ok(())
+    }
 }
 
-impl<X, T> DisplayType<X> for DebugDisplay<T>
-where
-    X: fmt::Debug,
-    T: DisplayType<X> + clap::Args,
-{
-    fn next_fmt(&self, x: &X) -> Option<String> {
-        self.debug
-            .then(|| self.fmt(x))
-            .or_else(|| self.next.next_fmt(x))
-    }
-    fn fmt(&self, x: &X) -> String {
-        format!("{x:?}")
+impl<X: ToTableRows> ToTable for Items<X> {
+    fn to_table(&self) -> Table {
+        let mut table = Table::new();
+
+        table
+            .set_content_arrangement(ContentArrangement::Dynamic)
+            .set_header(X::header());
+
+        for item in &self.0 {
+            table.add_row(item.to_row());
+        }
+
+        table
     }
 }
 
-#[derive(Parser)]
-struct ApiDisplay<T: clap::Args> {
-    /// Display as unformatted JSON
-    #[clap(long, group = "fmt_type")]
-    api: bool,
-    #[clap(flatten)]
-    next: T,
+/// A single output format, selectable with `--format`, or implied by the
+/// chosen subcommand when `--format` is absent.
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum OutputFormat {
+    Text,
+    Debug,
+    Api,
+    Json,
+    Yaml,
+    Toml,
+    Table,
 }
 
-impl<X, T> DisplayType<X> for ApiDisplay<T>
+/// Render `x` in the given `format`.
+fn format_as<X>(x: &X, format: OutputFormat) -> Result<String, Error>
 where
-    X: serde::Serialize,
-    T: DisplayType<X> + clap::Args,
+    X: fmt::Display + fmt::Debug + serde::Serialize + ToTable,
 {
-    fn next_fmt(&self, x: &X) -> Option<String> {
-        self.api
-            .then(|| self.fmt(x))
-            .or_else(|| self.next.next_fmt(x))
-    }
-    fn fmt(&self, x: &X) -> String {
-        serde_json::to_string(x).expect("Cannot serialize item to JSON")
+    match format {
+        OutputFormat::Text => Ok(format!("{x}")),
+        OutputFormat::Debug => Ok(format!("{x:?}")),
+        OutputFormat::Api => Ok(serde_json::to_string(x)?),
+        OutputFormat::Json => Ok(serde_json::to_string_pretty(x)?),
+        OutputFormat::Yaml => Ok(serde_yaml::to_string(x)?),
+        OutputFormat::Toml => Ok(toml::to_string_pretty(x)?),
+        OutputFormat::Table => Ok(x.to_table().to_string()),
     }
 }
 
-#[derive(Parser)]
-struct JsonDisplay<T: clap::Args> {
-    /// Display as pretty formatted JSON
-    #[clap(long, group = "fmt_type")]
-    json: bool,
-    #[clap(flatten)]
-    next: T,
-}
-
-impl<X, T> DisplayType<X> for JsonDisplay<T>
+/// Render `x` in the given `format` and write it to `out`, so embedders can
+/// redirect the formatted output into a buffer, file, or pipe instead of
+/// stdout.
+fn write_as<X>(x: &X, format: OutputFormat, out: &mut dyn io::Write) -> io::Result<()>
 where
-    X: serde::Serialize,
-    T: DisplayType<X> + clap::Args,
+    X: fmt::Display + fmt::Debug + serde::Serialize + ToTable,
 {
-    fn next_fmt(&self, x: &X) -> Option<String> {
-        self.json
-            .then(|| self.fmt(x))
-            .or_else(|| self.next.next_fmt(x))
-    }
-    fn fmt(&self, x: &X) -> String {
-        serde_json::to_string_pretty(x).expect("Cannot serialize item to JSON")
-    }
+    let rendered = format_as(x, format).map_err(io::Error::other)?;
+    writeln!(out, "{rendered}")
 }
 
-#[derive(Parser)]
-struct YamlDisplay<T: clap::Args> {
-    /// Display as YAML
-    #[clap(long, group = "fmt_type")]
-    yaml: bool,
-    #[clap(flatten)]
-    next: T,
+/// Where rendered output is written: stdout for normal results, or stderr
+/// when stdout is reserved for other data by an embedding program.
+enum OutputTarget {
+    Stdout,
+    Stderr,
 }
 
-impl<X, T> DisplayType<X> for YamlDisplay<T>
-where
-    X: serde::Serialize,
-    T: DisplayType<X> + clap::Args,
-{
-    fn next_fmt(&self, x: &X) -> Option<String> {
-        self.yaml
-            .then(|| self.fmt(x))
-            .or_else(|| self.next.next_fmt(x))
-    }
-    fn fmt(&self, x: &X) -> String {
-        serde_yaml::to_string(x).expect("Cannot serialize item to YAML")
-    }
+/// Execution context threaded through to output, following nitrocli's
+/// model of printing "to the output set in the given context."
+struct Context {
+    target: OutputTarget,
 }
 
-#[derive(Parser)]
-struct TableDisplay<T: clap::Args> {
-    /// Display as table
-    #[clap(long, group = "fmt_type", alias = "tabular")]
-    table: bool,
-    #[clap(flatten)]
-    next: T,
+impl Context {
+    fn writer(&self) -> Box<dyn io::Write> {
+        match self.target {
+            OutputTarget::Stdout => Box::new(io::stdout()),
+            OutputTarget::Stderr => Box::new(io::stderr()),
+        }
+    }
 }
 
-impl<X, T> DisplayType<X> for TableDisplay<T>
-where
-    X: ToTable,
-    T: DisplayType<X> + clap::Args,
-{
-    fn next_fmt(&self, x: &X) -> Option<String> {
-        self.table
-            .then(|| self.fmt(x))
-            .or_else(|| self.next.next_fmt(x))
-    }
-    fn fmt(&self, x: &X) -> String {
-        x.to_table().to_string()
-    }
+/// Arguments shared by every subcommand: an optional format override and a
+/// target stream, flattened into each `App` variant below.
+#[derive(Parser)]
+struct FormatArgs {
+    /// Select the output format directly instead of the subcommand's default
+    #[clap(long, value_enum)]
+    format: Option<OutputFormat>,
+    /// Write output to stderr instead of stdout
+    #[clap(long)]
+    stderr: bool,
 }
 
 #[derive(Parser)]
 enum App {
     /// Debug output by default
-    Debug {
-        #[clap(flatten)]
-        output: DebugDisplay<TextDisplay<ApiDisplay<NoDisplay>>>,
-    },
+    Debug(FormatArgs),
     /// Text output by default
-    Text {
-        #[clap(flatten)]
-        output: TextDisplay<DebugDisplay<ApiDisplay<NoDisplay>>>,
-    },
+    Text(FormatArgs),
     /// Unformatted JSON output by default
-    Api {
-        #[clap(flatten)]
-        output: ApiDisplay<TextDisplay<DebugDisplay<NoDisplay>>>,
-    },
+    Api(FormatArgs),
     /// Pretty formatted JSON output by default
-    Json {
-        #[clap(flatten)]
-        output: JsonDisplay<ApiDisplay<TextDisplay<DebugDisplay<NoDisplay>>>>,
-    },
+    Json(FormatArgs),
     /// YAML output by default
-    Yaml {
-        #[clap(flatten)]
-        output: YamlDisplay<JsonDisplay<ApiDisplay<TextDisplay<DebugDisplay<NoDisplay>>>>>,
-    },
+    Yaml(FormatArgs),
+    /// TOML output by default
+    Toml(FormatArgs),
     /// Table output by default
-    Table {
-        #[clap(flatten)]
-        output: TableDisplay<
-            YamlDisplay<JsonDisplay<ApiDisplay<TextDisplay<DebugDisplay<NoDisplay>>>>>,
-        >,
-    },
+    Table(FormatArgs),
 }
 
-#[derive(Debug, serde::Serialize)]
+#[derive(Debug, serde::Serialize, ToTable)]
 struct Foo {
+    #[table(rename = "Name")]
     name: String,
+    #[table(rename = "Value")]
     value: String,
 }
 
@@ -218,33 +184,92 @@ impl fmt::Display for Foo {
     }
 }
 
-impl ToTable for Foo {
-    fn to_table(&self) -> Table {
-        let mut table = Table::new();
+fn main() {
+    let app = App::parse();
 
-        table
-            .set_content_arrangement(ContentArrangement::Dynamic)
-            .set_header(Row::from(vec!["Name", "Value"]))
-            .add_row(Row::from(vec![&self.name, &self.value]));
+    let items = Items(vec![
+        Foo {
+            name: "Hello".to_string(),
+            value: "world".to_string(),
+        },
+        Foo {
+            name: "Foo".to_string(),
+            value: "bar".to_string(),
+        },
+    ]);
 
-        table
+    let (default_format, args) = match &app {
+        App::Debug(args) => (OutputFormat::Debug, args),
+        App::Text(args) => (OutputFormat::Text, args),
+        App::Api(args) => (OutputFormat::Api, args),
+        App::Json(args) => (OutputFormat::Json, args),
+        App::Yaml(args) => (OutputFormat::Yaml, args),
+        App::Toml(args) => (OutputFormat::Toml, args),
+        App::Table(args) => (OutputFormat::Table, args),
+    };
+    let format = args.format.unwrap_or(default_format);
+
+    let ctx = Context {
+        target: if args.stderr {
+            OutputTarget::Stderr
+        } else {
+            OutputTarget::Stdout
+        },
+    };
+    let mut out = ctx.writer();
+
+    if let Err(e) = write_as(&items, format, &mut *out) {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
     }
 }
 
-fn main() {
-    let app = App::parse();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let foo = Foo {
-        name: "Hello".to_string(),
-        value: "world".to_string(),
-    };
+    fn sample_items() -> Items<Foo> {
+        Items(vec![
+            Foo {
+                name: "Hello".to_string(),
+                value: "world".to_string(),
+            },
+            Foo {
+                name: "Foo".to_string(),
+                value: "bar".to_string(),
+            },
+        ])
+    }
+
+    #[test]
+    fn toml_format_handles_a_collection() {
+        let rendered = format_as(&sample_items(), OutputFormat::Toml)
+            .expect("toml serialization should succeed for a collection");
+        assert!(rendered.contains("[[items]]"));
+    }
+
+    #[test]
+    fn write_as_writes_rendered_output_to_the_given_sink() {
+        let mut buf = Vec::new();
+        write_as(&sample_items(), OutputFormat::Text, &mut buf).unwrap();
+        assert_eq!(buf, b"Hello=world\nFoo=bar\n");
+    }
 
-    match app {
-        App::Debug { output } => println!("{}", output.display(&foo)),
-        App::Text { output } => println!("{}", output.display(&foo)),
-        App::Api { output } => println!("{}", output.display(&foo)),
-        App::Json { output } => println!("{}", output.display(&foo)),
-        App::Yaml { output } => println!("{}", output.display(&foo)),
-        App::Table { output } => println!("{}", output.display(&foo)),
+    #[test]
+    fn write_as_covers_every_format() {
+        for format in [
+            OutputFormat::Text,
+            OutputFormat::Debug,
+            OutputFormat::Api,
+            OutputFormat::Json,
+            OutputFormat::Yaml,
+            OutputFormat::Toml,
+            OutputFormat::Table,
+        ] {
+            let mut buf = Vec::new();
+            write_as(&sample_items(), format, &mut buf)
+                .unwrap_or_else(|e| panic!("{format:?} should render a collection: {e}"));
+            assert!(!buf.is_empty());
+        }
     }
 }